@@ -1,23 +1,32 @@
-use std::{collections::HashMap, collections::HashSet, env, fs, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, collections::HashSet, env, fs, path::{Path, PathBuf}, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
+use chrono::TimeZone;
 use reqwest::Client;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use teloxide::{
     prelude::*,
-    types::Me,
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Me},
     utils::command::BotCommands,
 };
 use tokio::sync::RwLock;
 use tracing::{info, warn, error};
 
+mod notifier;
+use notifier::{send_resilient, ChatMigrations, Notifier, TelegramNotifier, WebhookNotifier};
+
 #[derive(Parser, Debug)]
 #[command(name = "zabbixbot", version, about = "Zabbix ↔ Telegram bot and setup utility")]
 struct Cli {
     /// Subcommand. If omitted, runs the Telegram bot.
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Путь к TOML-файлу конфигурации (см. Config). Если не задан, настройки берутся из env;
+    /// интервал поллинга и порог severity в env не читаются и получают значения по умолчанию.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -35,10 +44,118 @@ enum Command {
     Start,
     /// Показать ваш Telegram ID
     Id,
+    /// Добавить пользователя в allow-list (только владелец)
+    AddUser(i64),
+    /// Удалить пользователя из allow-list (только владелец)
+    DelUser(i64),
+    /// Показать список разрешённых пользователей (только владелец)
+    ListUsers,
+}
+
+/// Конфигурация бота. Загружается из TOML-файла (флаг `--config`), при этом
+/// секреты (токен бота, пароль Zabbix) можно переопределить через переменные
+/// окружения — удобно для docker-compose/k8s, где секреты приходят отдельно от
+/// остального конфига.
+#[derive(Deserialize, Debug, Default)]
+struct Config {
+    #[serde(default)]
+    zabbix: ZabbixConfig,
+    #[serde(default)]
+    telegram: TelegramConfig,
+    #[serde(default)]
+    channels: Vec<ChannelConfig>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ZabbixConfig {
+    url: Option<String>,
+    user: Option<String>,
+    password: Option<String>,
+    poll_interval_secs: Option<u64>,
+    severity_floor: Option<i64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct TelegramConfig {
+    token: Option<String>,
+    owner_id: Option<i64>,
+    allowed_users_path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChannelConfig {
+    /// Generic Slack-style incoming webhook (POST `{"text": "..."}`).
+    Webhook { url: String },
+    /// Extra Telegram chat(s) to fan alerts out to, distinct from the
+    /// interactive `allowed_users` push (no inline keyboard, just `plain`/`html`).
+    Telegram { chat_ids: Vec<i64> },
+}
+
+impl Config {
+    /// Читает TOML по `path`, если задан (иначе использует значения по умолчанию),
+    /// затем накладывает секреты из окружения поверх того, что пришло из файла.
+    fn load(path: Option<&PathBuf>) -> Result<Self> {
+        let mut config = match path {
+            Some(p) => {
+                let content = fs::read_to_string(p)
+                    .with_context(|| format!("Не удалось прочитать файл конфигурации: {}", p.display()))?;
+                toml::from_str(&content)
+                    .with_context(|| format!("Не удалось разобрать TOML конфигурацию: {}", p.display()))?
+            }
+            None => Config::default(),
+        };
+
+        if let Ok(token) = env::var("TELEGRAM_BOT_TOKEN") {
+            config.telegram.token = Some(token);
+        }
+        if let Ok(url) = env::var("ZBX_API_URL") {
+            config.zabbix.url = Some(url);
+        }
+        if let Ok(user) = env::var("ZBX_USER") {
+            config.zabbix.user = Some(user);
+        }
+        if let Ok(password) = env::var("ZBX_PASSWORD") {
+            config.zabbix.password = Some(password);
+        }
+
+        Ok(config)
+    }
 }
 
 struct AppState {
     allowed_users: RwLock<HashSet<i64>>, // хранится под Arc сверху
+    /// Авторизованный клиент Zabbix API, если заданы реквизиты (ZBX_API_URL и т.д.).
+    /// Нужен, чтобы бот мог подтверждать/закрывать проблемы по кнопкам без повторного логина.
+    zbx: Option<RwLock<ZbxClient>>,
+    /// user_id -> eventid проблемы, ожидающей текста заметки (после нажатия "Add note").
+    pending_notes: RwLock<HashMap<i64, String>>,
+    /// Telegram ID владельца бота (BOT_OWNER_ID). Владелец неявно считается allowed
+    /// и дополнительно может управлять allow-list'ом через /adduser, /deluser, /listusers.
+    owner_id: Option<i64>,
+    /// Remaps группа->супергруппа, накопленные send_resilient'ом.
+    chat_migrations: ChatMigrations,
+    /// Ключи вида "<eventid>:<action>" для уже выполненных нажатий ack/close,
+    /// чтобы повторное нажатие было no-op'ом, а не повторным вызовом Zabbix API
+    /// с бесконечным дописыванием "✅ ... by ..." в текст сообщения.
+    acted_events: RwLock<HashSet<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthLevel {
+    Owner,
+    Allowed,
+    Denied,
+}
+
+async fn authorize(state: &AppState, user_id: i64) -> AuthLevel {
+    if state.owner_id == Some(user_id) {
+        return AuthLevel::Owner;
+    }
+    if state.allowed_users.read().await.contains(&user_id) {
+        return AuthLevel::Allowed;
+    }
+    AuthLevel::Denied
 }
 
 fn read_allowed_users(path: &PathBuf) -> Result<HashSet<i64>> {
@@ -66,6 +183,31 @@ fn read_allowed_users(path: &PathBuf) -> Result<HashSet<i64>> {
     Ok(set)
 }
 
+/// Атомарно перезаписывает файл по `path`: пишет во временный файл рядом и
+/// переименовывает его поверх целевого, чтобы падение процесса в середине
+/// записи никогда не оставляло частично записанный (и потому некорректно
+/// читаемый при следующем старте) файл. Имя временного файла фиксировано для
+/// каждого `path`, поэтому одновременная запись одного `path` из нескольких
+/// процессов не защищена.
+fn atomic_write(path: &PathBuf, content: &str) -> Result<()> {
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(|| PathBuf::from("."));
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("out.tmp");
+    let tmp_path = dir.join(format!(".{}.tmp", file_name));
+
+    fs::write(&tmp_path, content)
+        .with_context(|| format!("Не удалось записать временный файл {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Не удалось переименовать {} в {}", tmp_path.display(), path.display()))?;
+    Ok(())
+}
+
+fn write_allowed_users(path: &PathBuf, users: &HashSet<i64>) -> Result<()> {
+    let mut ids: Vec<i64> = users.iter().copied().collect();
+    ids.sort_unstable();
+    let content = ids.iter().map(i64::to_string).collect::<Vec<_>>().join("\n");
+    atomic_write(path, &content)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,6 +232,26 @@ mod tests {
 
         std::fs::remove_file(&fpath).ok();
     }
+
+    #[tokio::test]
+    async fn test_write_allowed_users_roundtrips_via_atomic_rename() {
+        let mut fpath = std::env::temp_dir();
+        fpath.push(format!("allowed_users_write_test_{}.txt", std::process::id()));
+        std::fs::remove_file(&fpath).ok();
+
+        let mut users = HashSet::new();
+        users.insert(111);
+        users.insert(222);
+        write_allowed_users(&fpath, &users).unwrap();
+
+        let reread = read_allowed_users(&fpath).unwrap();
+        assert_eq!(reread, users);
+
+        let tmp_path = fpath.with_file_name(format!(".{}.tmp", fpath.file_name().unwrap().to_str().unwrap()));
+        assert!(!tmp_path.exists());
+
+        std::fs::remove_file(&fpath).ok();
+    }
 }
 
 #[derive(Serialize)]
@@ -173,6 +335,356 @@ impl ZbxClient {
     }
 }
 
+// --- Активный поллинг проблем Zabbix и пуш-уведомления ---
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZbxHost {
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZbxProblem {
+    eventid: String,
+    name: String,
+    severity: String,
+    clock: String,
+    #[serde(default)]
+    r_eventid: String,
+    #[serde(default)]
+    hosts: Vec<ZbxHost>,
+}
+
+/// Шаблоны уведомлений, общие для всех каналов. Каждый [`Notifier`] сам решает,
+/// какое из полей ему подходит (Telegram — `*_html`, если задан, иначе
+/// `*_plain`; Slack-вебхук — всегда `*_plain`).
+#[derive(Clone)]
+struct AlertTemplates {
+    alert_subject: String,
+    alert_plain: String,
+    alert_html: Option<String>,
+    resolve_subject: String,
+    resolve_plain: String,
+}
+
+impl AlertTemplates {
+    fn from_env() -> Self {
+        Self {
+            alert_subject: env::var("ZBX_ALERT_SUBJECT_TEMPLATE")
+                .unwrap_or_else(|_| "{HOST.NAME}: {EVENT.NAME}".to_string()),
+            alert_plain: env::var("ZBX_ALERT_TEMPLATE").unwrap_or_else(|_| {
+                "🔴 {EVENT.NAME}\nHost: {HOST.NAME}\nSeverity: {TRIGGER.SEVERITY}\nSince: {EVENT.DATE}".to_string()
+            }),
+            alert_html: env::var("ZBX_ALERT_HTML_TEMPLATE").ok(),
+            resolve_subject: env::var("ZBX_RESOLVE_SUBJECT_TEMPLATE")
+                .unwrap_or_else(|_| "{HOST.NAME}: resolved".to_string()),
+            resolve_plain: env::var("ZBX_RESOLVE_TEMPLATE")
+                .unwrap_or_else(|_| "✅ Resolved: {EVENT.NAME}\nHost: {HOST.NAME}".to_string()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PollConfig {
+    interval: Duration,
+    severity_floor: i64,
+    templates: AlertTemplates,
+}
+
+impl PollConfig {
+    fn from_config(config: &Config) -> Self {
+        let interval_secs = config.zabbix.poll_interval_secs.unwrap_or(60);
+        let severity_floor = config.zabbix.severity_floor.unwrap_or(0);
+        Self { interval: Duration::from_secs(interval_secs), severity_floor, templates: AlertTemplates::from_env() }
+    }
+}
+
+/// Строит дополнительные каналы уведомлений (сверх интерактивного Telegram-пуша
+/// в `allowed_users`) из списка `config.channels`, с резервным чтением
+/// `ZBX_WEBHOOK_URL` для конфигураций, ещё не перенесённых на TOML-файл.
+fn notifiers_from_config(config: &Config, bot: &Bot, migrations: &ChatMigrations) -> Vec<Box<dyn Notifier>> {
+    if !config.channels.is_empty() {
+        return config.channels.iter().map(|channel| match channel {
+            ChannelConfig::Webhook { url } => Box::new(WebhookNotifier::new(url.clone())) as Box<dyn Notifier>,
+            ChannelConfig::Telegram { chat_ids } => Box::new(TelegramNotifier::new(
+                bot.clone(),
+                chat_ids.iter().map(|&id| ChatId(id)).collect(),
+                migrations.clone(),
+            )) as Box<dyn Notifier>,
+        }).collect();
+    }
+
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Ok(hook_url) = env::var("ZBX_WEBHOOK_URL") {
+        notifiers.push(Box::new(WebhookNotifier::new(hook_url)));
+    }
+    notifiers
+}
+
+fn severity_name(severity: &str) -> String {
+    match severity {
+        "0" => "Not classified",
+        "1" => "Information",
+        "2" => "Warning",
+        "3" => "Average",
+        "4" => "High",
+        "5" => "Disaster",
+        _ => "Unknown",
+    }.to_string()
+}
+
+fn format_event_date(clock: &str) -> String {
+    clock.parse::<i64>().ok()
+        .and_then(|ts| chrono::Utc.timestamp_opt(ts, 0).single())
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| clock.to_string())
+}
+
+fn render_problem_template(tpl: &str, problem: &ZbxProblem) -> String {
+    let host = problem.hosts.first()
+        .map(|h| h.name.clone().unwrap_or_else(|| h.host.clone()))
+        .unwrap_or_else(|| "-".to_string());
+    tpl.replace("{EVENT.NAME}", &problem.name)
+        .replace("{HOST.NAME}", &host)
+        .replace("{TRIGGER.SEVERITY}", &severity_name(&problem.severity))
+        .replace("{EVENT.DATE}", &format_event_date(&problem.clock))
+}
+
+#[cfg(test)]
+mod poller_template_tests {
+    use super::*;
+
+    #[test]
+    fn test_severity_name_maps_known_levels_and_falls_back_to_unknown() {
+        assert_eq!(severity_name("0"), "Not classified");
+        assert_eq!(severity_name("4"), "High");
+        assert_eq!(severity_name("5"), "Disaster");
+        assert_eq!(severity_name("99"), "Unknown");
+    }
+
+    #[test]
+    fn test_format_event_date_formats_unix_timestamp() {
+        // 2021-01-01T00:00:00Z
+        assert_eq!(format_event_date("1609459200"), "2021-01-01 00:00:00");
+    }
+
+    #[test]
+    fn test_format_event_date_falls_back_to_raw_string_on_bad_input() {
+        assert_eq!(format_event_date("not-a-timestamp"), "not-a-timestamp");
+    }
+
+    #[test]
+    fn test_render_problem_template_substitutes_all_placeholders() {
+        let problem = ZbxProblem {
+            eventid: "1".into(),
+            name: "CPU load is high".into(),
+            severity: "4".into(),
+            clock: "1609459200".into(),
+            r_eventid: "0".into(),
+            hosts: vec![ZbxHost { host: "srv1".into(), name: Some("srv1.example.com".into()) }],
+        };
+        let rendered = render_problem_template(
+            "{EVENT.NAME} on {HOST.NAME} [{TRIGGER.SEVERITY}] at {EVENT.DATE}",
+            &problem,
+        );
+        assert_eq!(rendered, "CPU load is high on srv1.example.com [High] at 2021-01-01 00:00:00");
+    }
+
+    #[test]
+    fn test_render_problem_template_falls_back_to_host_field_without_visible_name() {
+        let problem = ZbxProblem {
+            eventid: "2".into(),
+            name: "Disk full".into(),
+            severity: "3".into(),
+            clock: "1609459200".into(),
+            r_eventid: "0".into(),
+            hosts: vec![ZbxHost { host: "srv2".into(), name: None }],
+        };
+        assert_eq!(render_problem_template("{HOST.NAME}", &problem), "srv2");
+    }
+
+    #[test]
+    fn test_render_problem_template_uses_dash_when_no_hosts() {
+        let problem = ZbxProblem {
+            eventid: "3".into(),
+            name: "Ping fail".into(),
+            severity: "2".into(),
+            clock: "1609459200".into(),
+            r_eventid: "0".into(),
+            hosts: vec![],
+        };
+        assert_eq!(render_problem_template("{HOST.NAME}", &problem), "-");
+    }
+}
+
+fn read_announced_events(path: &PathBuf) -> Result<HashSet<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e).with_context(|| format!("Не удалось прочитать файл announced_events: {}", path.display())),
+    }
+}
+
+fn write_announced_events(path: &PathBuf, events: &HashSet<String>) -> Result<()> {
+    let content = events.iter().cloned().collect::<Vec<_>>().join("\n");
+    atomic_write(path, &content)
+}
+
+#[derive(Serialize)]
+struct ProblemGetParams<'a> {
+    output: &'a [&'a str],
+    selectHosts: &'a [&'a str],
+    recent: bool,
+    sortfield: &'a [&'a str],
+    sortorder: &'a str,
+}
+
+async fn fetch_problems(zbx: &ZbxClient, recent: bool) -> Result<Vec<ZbxProblem>> {
+    let params = ProblemGetParams {
+        output: &["eventid", "name", "severity", "clock", "r_eventid"],
+        selectHosts: &["host", "name"],
+        recent,
+        sortfield: &["eventid"],
+        sortorder: "DESC",
+    };
+    zbx.rpc("problem.get", &params).await
+}
+
+/// Кнопки под пуш-уведомлением о проблеме: callback_data вида "zbxack:<action>:<eventid>".
+fn problem_keyboard(eventid: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("✅ Acknowledge", format!("zbxack:ack:{}", eventid)),
+        InlineKeyboardButton::callback("📝 Add note", format!("zbxack:note:{}", eventid)),
+        InlineKeyboardButton::callback("🔒 Close", format!("zbxack:close:{}", eventid)),
+    ]])
+}
+
+#[derive(Serialize)]
+struct EventAcknowledgeParams<'a> {
+    eventids: &'a [&'a str],
+    action: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+/// action: 1 = close problem, 2 = acknowledge, 4 = add message (битовая маска, можно складывать).
+async fn acknowledge_event(zbx: &ZbxClient, eventid: &str, action: i64, message: Option<&str>) -> Result<()> {
+    let params = EventAcknowledgeParams { eventids: &[eventid], action, message };
+    let _res: serde_json::Value = zbx.rpc("event.acknowledge", &params).await?;
+    Ok(())
+}
+
+/// Фоновый цикл поллинга: раз в `config.interval` спрашивает Zabbix о новых
+/// и закрытых проблемах и рассылает уведомления всем пользователям из allowed_users.
+async fn run_problem_poller(
+    bot: Bot,
+    state: Arc<AppState>,
+    announced_path: PathBuf,
+    config: PollConfig,
+    extra_notifiers: Vec<Box<dyn Notifier>>,
+) {
+    let Some(zbx_lock) = state.zbx.as_ref() else {
+        warn!("Поллер запущен без авторизованного клиента Zabbix API — выхожу");
+        return;
+    };
+
+    let mut announced = match read_announced_events(&announced_path) {
+        Ok(set) => set,
+        Err(e) => {
+            error!(error = %e, "Не удалось прочитать announced_events, продолжаю с пустым множеством");
+            HashSet::new()
+        }
+    };
+
+    // Прайминг: открытые на момент старта проблемы не должны считаться "новыми"
+    match fetch_problems(&*zbx_lock.read().await, false).await {
+        Ok(problems) => {
+            for p in problems.iter().filter(|p| p.r_eventid == "0") {
+                announced.insert(p.eventid.clone());
+            }
+            info!(count = announced.len(), "Прайминг поллера завершён текущими открытыми проблемами");
+        }
+        Err(e) => warn!(error = %e, "Не удалось выполнить прайминг поллера проблем"),
+    }
+    if let Err(e) = write_announced_events(&announced_path, &announced) {
+        warn!(error = %e, "Не удалось сохранить announced_events после прайминга");
+    }
+
+    let mut ticker = tokio::time::interval(config.interval);
+    loop {
+        ticker.tick().await;
+
+        let problems = match fetch_problems(&*zbx_lock.read().await, true).await {
+            Ok(p) => p,
+            Err(e) => {
+                warn!(error = %e, "Не удалось получить список проблем Zabbix");
+                continue;
+            }
+        };
+
+        let mut changed = false;
+        let recipients: Vec<i64> = state.allowed_users.read().await.iter().copied().collect();
+
+        for problem in &problems {
+            let severity: i64 = problem.severity.parse().unwrap_or(0);
+            if problem.r_eventid == "0" {
+                // severity_floor гасит только отправку алерта о новой проблеме; резолвы
+                // обрабатываются ниже безусловно, иначе проблема, примированная в
+                // announced при старте ниже floor'а, никогда бы из него не убралась.
+                if severity < config.severity_floor {
+                    continue;
+                }
+                if announced.insert(problem.eventid.clone()) {
+                    changed = true;
+
+                    // Интерактивный пуш в Telegram с кнопками подтверждения/закрытия
+                    let text = render_problem_template(&config.templates.alert_plain, problem);
+                    let keyboard = problem_keyboard(&problem.eventid);
+                    for &uid in &recipients {
+                        if let Err(e) = send_resilient(&bot, &state.chat_migrations, ChatId(uid), text.clone(), None, Some(keyboard.clone())).await {
+                            warn!(user_id = uid, error = %e, "Не удалось отправить уведомление о проблеме");
+                        }
+                    }
+
+                    // Остальные настроенные каналы (Slack-вебхук и т.п.)
+                    let subject = render_problem_template(&config.templates.alert_subject, problem);
+                    let plain = render_problem_template(&config.templates.alert_plain, problem);
+                    let html = config.templates.alert_html.as_ref().map(|tpl| render_problem_template(tpl, problem));
+                    for notifier in &extra_notifiers {
+                        if let Err(e) = notifier.send(&subject, &plain, html.as_deref()).await {
+                            warn!(error = %e, "Не удалось отправить уведомление о проблеме через доп. канал");
+                        }
+                    }
+                }
+            } else if announced.remove(&problem.eventid) {
+                changed = true;
+
+                let text = render_problem_template(&config.templates.resolve_plain, problem);
+                for &uid in &recipients {
+                    if let Err(e) = send_resilient(&bot, &state.chat_migrations, ChatId(uid), text.clone(), None, None).await {
+                        warn!(user_id = uid, error = %e, "Не удалось отправить уведомление о закрытии проблемы");
+                    }
+                }
+
+                let subject = render_problem_template(&config.templates.resolve_subject, problem);
+                for notifier in &extra_notifiers {
+                    if let Err(e) = notifier.send(&subject, &text, None).await {
+                        warn!(error = %e, "Не удалось отправить уведомление о закрытии проблемы через доп. канал");
+                    }
+                }
+            }
+        }
+
+        if changed {
+            if let Err(e) = write_announced_events(&announced_path, &announced) {
+                warn!(error = %e, "Не удалось сохранить announced_events");
+            }
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct MediaType {
     mediatypeid: String,
@@ -195,11 +707,12 @@ struct UserMedia {
     period: String,      // e.g., 1-7,00:00-24:00
 }
 
-async fn zbx_setup() -> Result<()> {
-    // Читаем конфиг из окружения
-    let url = env::var("ZBX_API_URL").context("ZBX_API_URL is required, e.g. http://zabbix.local/zabbix/api_jsonrpc.php")?;
-    let user = env::var("ZBX_USER").unwrap_or_else(|_| "Admin".to_string());
-    let pass = env::var("ZBX_PASSWORD").context("ZBX_PASSWORD is required")?;
+async fn zbx_setup(config: &Config) -> Result<()> {
+    // Реквизиты Zabbix — из конфига (zabbix.url/user/password), с переопределением из env
+    let url = config.zabbix.url.clone()
+        .context("ZBX_API_URL/zabbix.url is required, e.g. http://zabbix.local/zabbix/api_jsonrpc.php")?;
+    let user = config.zabbix.user.clone().unwrap_or_else(|| "Admin".to_string());
+    let pass = config.zabbix.password.clone().context("ZBX_PASSWORD/zabbix.password is required")?;
     let user_alias = env::var("ZBX_USER_ALIAS").unwrap_or_else(|_| "Admin".to_string());
     let chat_id = env::var("ZBX_CHAT_ID").context("ZBX_CHAT_ID is required (e.g., 1349552926)")?;
     let action_name = env::var("ZBX_ACTION_NAME").unwrap_or_else(|_| "Send Telegram alerts".to_string());
@@ -221,9 +734,8 @@ async fn zbx_setup() -> Result<()> {
     info!(mediatypeid = %mediatypeid, "Found media type 'Telegram'");
 
     // Обновим токен бота в параметрах media type, если есть соответствующий параметр
-    if let Ok(bot_token) = env::var("TELEGRAM_BOT_TOKEN")
-        .or_else(|_| env::var("ZBX_BOT_TOKEN"))
-    {
+    let bot_token_opt = config.telegram.token.clone().or_else(|| env::var("ZBX_BOT_TOKEN").ok());
+    if let Some(bot_token) = bot_token_opt {
         if let Some(params) = mt.parameters.clone() {
             let mut needs_update = false;
             let mut updated_params: Vec<HashMap<String, String>> = Vec::with_capacity(params.len());
@@ -332,41 +844,82 @@ async fn main() -> Result<()> {
 
     // Проверка режима запуска: CLI субкоманда или RUN_MODE
     let cli = Cli::parse();
+    let config = Config::load(cli.config.as_ref())?;
     let run_mode_env = env::var("RUN_MODE").unwrap_or_default();
     match (cli.command, run_mode_env.as_str()) {
         (Some(Commands::ZbxSetup), _) | (None, "zbx-setup") => {
-            return zbx_setup().await;
+            return zbx_setup(&config).await;
         }
         _ => {
             // Бот по умолчанию
         }
     }
 
-    let token = env::var("TELEGRAM_BOT_TOKEN")
-        .context("Переменная окружения TELEGRAM_BOT_TOKEN не задана")?;
+    let token = config.telegram.token.clone()
+        .context("Токен бота не задан ни в конфиге (telegram.token), ни в TELEGRAM_BOT_TOKEN")?;
 
-    let allowed_path = env::var("ALLOWED_USERS_PATH")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/bot/allowed_users.txt"));
+    let allowed_path = config.telegram.allowed_users_path.clone()
+        .or_else(|| env::var("ALLOWED_USERS_PATH").ok().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("/bot/allowed_users.txt"));
 
     let allowed_users = read_allowed_users(&allowed_path)?;
     info!(count = allowed_users.len(), path = %allowed_path.display(), "Список разрешенных пользователей загружен");
 
-    let state = Arc::new(AppState { allowed_users: RwLock::new(allowed_users) });
+    // Если заданы реквизиты Zabbix API, логинимся один раз и держим клиент в AppState,
+    // чтобы им могли пользоваться и поллер, и обработчик кнопок подтверждения.
+    let zbx = match config.zabbix.url.clone() {
+        Some(zbx_url) => {
+            let zbx_user = config.zabbix.user.clone().unwrap_or_else(|| "Admin".to_string());
+            let zbx_pass = config.zabbix.password.clone()
+                .context("Пароль Zabbix не задан ни в конфиге (zabbix.password), ни в ZBX_PASSWORD")?;
+            let mut client = ZbxClient::new(zbx_url);
+            client.login(&zbx_user, &zbx_pass).await.context("Не удалось авторизоваться в Zabbix API")?;
+            Some(RwLock::new(client))
+        }
+        None => {
+            info!("zabbix.url не задан — активный поллинг и подтверждение проблем отключены");
+            None
+        }
+    };
+    let zbx_enabled = zbx.is_some();
+
+    let owner_id = config.telegram.owner_id.or_else(|| env::var("BOT_OWNER_ID").ok().and_then(|v| v.parse().ok()));
+    if owner_id.is_none() {
+        warn!("BOT_OWNER_ID не задан — команды /adduser, /deluser и /listusers недоступны");
+    }
+
+    let state = Arc::new(AppState {
+        allowed_users: RwLock::new(allowed_users),
+        zbx,
+        pending_notes: RwLock::new(HashMap::new()),
+        owner_id,
+        chat_migrations: Arc::new(RwLock::new(HashMap::new())),
+        acted_events: RwLock::new(HashSet::new()),
+    });
 
     let bot = Bot::new(token);
     let me: Me = bot.get_me().await?;
     info!(username = %me.username(), id = %me.id, "Бот запущен");
 
+    if zbx_enabled {
+        let announced_path = allowed_path.with_file_name("announced_events.txt");
+        let poll_config = PollConfig::from_config(&config);
+        info!(interval = ?poll_config.interval, severity_floor = poll_config.severity_floor, "Запускаю поллинг проблем Zabbix");
+        let extra_notifiers = notifiers_from_config(&config, &bot, &state.chat_migrations);
+        tokio::spawn(run_problem_poller(bot.clone(), state.clone(), announced_path, poll_config, extra_notifiers));
+    }
+
     // Роутинг команд
-    let handler = Update::filter_message()
-        .branch(dptree::entry()
-            .filter_command::<Command>()
-            .endpoint(handle_command))
-        .branch(dptree::endpoint(handle_message));
+    let handler = dptree::entry()
+        .branch(Update::filter_message()
+            .branch(dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(handle_command))
+            .branch(dptree::endpoint(handle_message)))
+        .branch(Update::filter_callback_query().endpoint(handle_callback));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![state.clone()])
+        .dependencies(dptree::deps![state.clone(), allowed_path.clone()])
         .enable_ctrlc_handler()
         .default_handler(|upd| async move {
             warn!(?upd, "Необработанное событие");
@@ -380,8 +933,7 @@ async fn main() -> Result<()> {
 }
 
 async fn is_authorized(state: &AppState, user_id: i64) -> bool {
-    let guard = state.allowed_users.read().await;
-    guard.contains(&user_id)
+    authorize(state, user_id).await != AuthLevel::Denied
 }
 
 async fn handle_command(
@@ -389,23 +941,77 @@ async fn handle_command(
     msg: Message,
     cmd: Command,
     state: Arc<AppState>,
+    allowed_path: PathBuf,
 ) -> Result<()> {
     let user_id = msg.from().map(|u| u.id.0 as i64);
     match (cmd, user_id) {
         (Command::Help, _) => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string()).await?;
+            send_resilient(&bot, &state.chat_migrations, msg.chat.id, Command::descriptions().to_string(), None, None).await?;
         }
         (Command::Start, Some(uid)) => {
             if !is_authorized(&state, uid).await {
                 warn!(user_id = uid, "Неавторизованный пользователь. Игнорирую...");
-                bot.send_message(msg.chat.id, "Access denied").await?;
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Access denied", None, None).await?;
             } else {
                 info!(user_id = uid, "Авторизованный пользователь");
-                bot.send_message(msg.chat.id, "Login successful").await?;
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Login successful", None, None).await?;
             }
         }
         (Command::Id, Some(uid)) => {
-            bot.send_message(msg.chat.id, format!("Ваш Telegram ID: {}", uid)).await?;
+            send_resilient(&bot, &state.chat_migrations, msg.chat.id, format!("Ваш Telegram ID: {}", uid), None, None).await?;
+        }
+        (Command::AddUser(id), Some(uid)) => {
+            if authorize(&state, uid).await != AuthLevel::Owner {
+                warn!(user_id = uid, "Попытка /adduser без прав владельца");
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Access denied", None, None).await?;
+            } else {
+                let mut guard = state.allowed_users.write().await;
+                guard.insert(id);
+                match write_allowed_users(&allowed_path, &guard) {
+                    Ok(()) => {
+                        info!(owner_id = uid, added = id, "Пользователь добавлен в allow-list");
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, format!("Пользователь {} добавлен", id), None, None).await?;
+                    }
+                    Err(e) => {
+                        error!(error = %e, "Не удалось сохранить allowed_users после /adduser");
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Не удалось сохранить список пользователей", None, None).await?;
+                    }
+                }
+            }
+        }
+        (Command::DelUser(id), Some(uid)) => {
+            if authorize(&state, uid).await != AuthLevel::Owner {
+                warn!(user_id = uid, "Попытка /deluser без прав владельца");
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Access denied", None, None).await?;
+            } else {
+                let mut guard = state.allowed_users.write().await;
+                guard.remove(&id);
+                match write_allowed_users(&allowed_path, &guard) {
+                    Ok(()) => {
+                        info!(owner_id = uid, removed = id, "Пользователь удалён из allow-list");
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, format!("Пользователь {} удалён", id), None, None).await?;
+                    }
+                    Err(e) => {
+                        error!(error = %e, "Не удалось сохранить allowed_users после /deluser");
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Не удалось сохранить список пользователей", None, None).await?;
+                    }
+                }
+            }
+        }
+        (Command::ListUsers, Some(uid)) => {
+            if authorize(&state, uid).await != AuthLevel::Owner {
+                warn!(user_id = uid, "Попытка /listusers без прав владельца");
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Access denied", None, None).await?;
+            } else {
+                let mut ids: Vec<i64> = state.allowed_users.read().await.iter().copied().collect();
+                ids.sort_unstable();
+                let text = if ids.is_empty() {
+                    "Allow-list пуст".to_string()
+                } else {
+                    ids.iter().map(i64::to_string).collect::<Vec<_>>().join("\n")
+                };
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, text, None, None).await?;
+            }
         }
         (_, None) => {
             warn!("Сообщение без поля from");
@@ -422,11 +1028,121 @@ async fn handle_message(
     let uid = match msg.from() { Some(u) => u.id.0 as i64, None => { return Ok(()); } };
     if !is_authorized(&state, uid).await {
         warn!(user_id = uid, "Неавторизованный пользователь. Игнорирую...");
-        bot.send_message(msg.chat.id, "Access denied").await.ok();
+        send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Access denied", None, None).await.ok();
+        return Ok(());
+    }
+
+    // Если мы ждём от этого пользователя текст заметки к проблеме — это он и есть
+    let pending_eventid = state.pending_notes.write().await.remove(&uid);
+    if let Some(eventid) = pending_eventid {
+        let Some(text) = msg.text() else {
+            send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Заметка должна быть текстом. Попробуйте ещё раз.", None, None).await?;
+            state.pending_notes.write().await.insert(uid, eventid);
+            return Ok(());
+        };
+        match state.zbx.as_ref() {
+            Some(zbx_lock) => {
+                let res = acknowledge_event(&*zbx_lock.read().await, &eventid, 4, Some(text)).await;
+                match res {
+                    Ok(()) => {
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, format!("Заметка добавлена к проблеме #{}", eventid), None, None).await?;
+                    }
+                    Err(e) => {
+                        error!(eventid = %eventid, error = %e, "Не удалось добавить заметку в Zabbix");
+                        send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Не удалось добавить заметку в Zabbix", None, None).await?;
+                    }
+                }
+            }
+            None => {
+                send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Zabbix API недоступен боту", None, None).await?;
+            }
+        }
         return Ok(());
     }
 
     // Экономно: просто отвечаем подсказкой на любое сообщение
-    bot.send_message(msg.chat.id, "Используйте /start для проверки доступа или /id для получения вашего ID").await?;
+    send_resilient(&bot, &state.chat_migrations, msg.chat.id, "Используйте /start для проверки доступа или /id для получения вашего ID", None, None).await?;
+    Ok(())
+}
+
+async fn handle_callback(bot: Bot, q: CallbackQuery, state: Arc<AppState>) -> Result<()> {
+    let user_id = q.from.id.0 as i64;
+    if !is_authorized(&state, user_id).await {
+        warn!(user_id, "Неавторизованное нажатие кнопки. Игнорирую...");
+        bot.answer_callback_query(q.id).text("Access denied").show_alert(true).await.ok();
+        return Ok(());
+    }
+
+    let data = q.data.clone().unwrap_or_default();
+    let mut parts = data.splitn(3, ':');
+    if parts.next() != Some("zbxack") {
+        bot.answer_callback_query(q.id).await.ok();
+        return Ok(());
+    }
+    let action = parts.next().unwrap_or("").to_string();
+    let eventid = parts.next().unwrap_or("").to_string();
+    if eventid.is_empty() {
+        bot.answer_callback_query(q.id).await.ok();
+        return Ok(());
+    }
+
+    let Some(msg) = q.message.clone() else {
+        bot.answer_callback_query(q.id).await.ok();
+        return Ok(());
+    };
+
+    match action.as_str() {
+        "note" => {
+            state.pending_notes.write().await.insert(user_id, eventid.clone());
+            bot.answer_callback_query(q.id).await.ok();
+            send_resilient(&bot, &state.chat_migrations, msg.chat.id, format!("Отправьте текст заметки для проблемы #{} следующим сообщением", eventid), None, None).await?;
+        }
+        "ack" | "close" => {
+            let action_key = format!("{}:{}", eventid, action);
+            if state.acted_events.read().await.contains(&action_key) {
+                // Уже выполнено по более раннему нажатию — no-op, иначе при повторных
+                // нажатиях текст сообщения рос бы бесконечно (см. ревью chunk0-2).
+                bot.answer_callback_query(q.id).text("Уже выполнено").await.ok();
+                return Ok(());
+            }
+
+            let Some(zbx_lock) = state.zbx.as_ref() else {
+                bot.answer_callback_query(q.id).text("Zabbix API недоступен боту").show_alert(true).await.ok();
+                return Ok(());
+            };
+            let zbx_action = if action == "close" { 1 } else { 2 };
+            match acknowledge_event(&*zbx_lock.read().await, &eventid, zbx_action, None).await {
+                Ok(()) => {
+                    state.acted_events.write().await.insert(action_key);
+
+                    let who = q.from.username.clone().unwrap_or_else(|| q.from.id.to_string());
+                    let verb = if action == "close" { "closed" } else { "acknowledged" };
+                    bot.answer_callback_query(q.id).text("Готово").await.ok();
+                    if let Some(text) = msg.text() {
+                        let updated = format!("{}\n\n✅ {} by {}", text, verb, who);
+                        // "Close" — терминальное действие: явно убираем клавиатуру (иначе
+                        // Telegram оставит прежнюю разметку нетронутой), чтобы на закрытой
+                        // проблеме не оставалось кликабельных кнопок.
+                        let keyboard = if action == "close" {
+                            InlineKeyboardMarkup::new(Vec::<Vec<InlineKeyboardButton>>::new())
+                        } else {
+                            problem_keyboard(&eventid)
+                        };
+                        bot.edit_message_text(msg.chat.id, msg.id, updated)
+                            .reply_markup(keyboard)
+                            .await.ok();
+                    }
+                }
+                Err(e) => {
+                    error!(eventid = %eventid, error = %e, "Не удалось подтвердить проблему в Zabbix");
+                    bot.answer_callback_query(q.id).text("Ошибка Zabbix API").show_alert(true).await.ok();
+                }
+            }
+        }
+        _ => {
+            bot.answer_callback_query(q.id).await.ok();
+        }
+    }
+
     Ok(())
 }
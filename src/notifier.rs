@@ -0,0 +1,189 @@
+//! Подключаемые каналы доставки уведомлений о событиях Zabbix.
+//!
+//! [`Notifier`] умеет только доставить уже отрендеренное сообщение и ничего
+//! не знает об источнике содержимого. Это позволяет поллингу рассылать одну
+//! обнаруженную проблему в Telegram, Slack-вебхук и т.п., не привязываясь
+//! жёстко ни к одному конкретному транспорту.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+use teloxide::{prelude::*, types::{ChatId, ParseMode}, RequestError};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+/// Запоминает remaps группа→супергруппа (`migrate_to_chat_id`), чтобы
+/// последующие отправки в старый chat_id прозрачно перенаправлялись в новый.
+/// Общий для всех нотификаторов, чтобы миграция, обнаруженная одним
+/// отправителем, учитывалась всеми остальными.
+pub type ChatMigrations = Arc<RwLock<HashMap<i64, i64>>>;
+
+async fn resolve_chat_id(migrations: &ChatMigrations, chat_id: ChatId) -> ChatId {
+    let map = migrations.read().await;
+    let mut id = chat_id.0;
+    while let Some(&next) = map.get(&id) {
+        if next == id { break; }
+        id = next;
+    }
+    ChatId(id)
+}
+
+/// Отправляет сообщение устойчиво к ограничению частоты запросов Telegram и
+/// миграции чата: при `429 Too Many Requests` ждёт присланный сервером
+/// `retry_after` (для остальных временных ошибок — экспоненциальный backoff),
+/// а при `migrate_to_chat_id` прозрачно повторяет отправку в новый chat_id и
+/// запоминает remap в `migrations` для последующих вызовов.
+pub async fn send_resilient(
+    bot: &Bot,
+    migrations: &ChatMigrations,
+    chat_id: ChatId,
+    text: impl Into<String>,
+    parse_mode: Option<ParseMode>,
+    reply_markup: Option<teloxide::types::InlineKeyboardMarkup>,
+) -> Result<Message> {
+    let text = text.into();
+    let mut chat_id = resolve_chat_id(migrations, chat_id).await;
+    let mut attempt = 0u32;
+    loop {
+        attempt += 1;
+        let mut req = bot.send_message(chat_id, text.clone());
+        if let Some(pm) = parse_mode {
+            req = req.parse_mode(pm);
+        }
+        if let Some(kb) = reply_markup.clone() {
+            req = req.reply_markup(kb);
+        }
+
+        match req.await {
+            Ok(msg) => return Ok(msg),
+            Err(RequestError::RetryAfter(delay)) => {
+                warn!(?chat_id, ?delay, attempt, "Telegram вернул 429, жду перед повтором");
+                tokio::time::sleep(delay).await;
+            }
+            Err(RequestError::MigrateToChatId(new_id)) => {
+                info!(old = ?chat_id, new = new_id, "Группа мигрировала в супергруппу, повторяю с новым chat_id");
+                migrations.write().await.insert(chat_id.0, new_id);
+                chat_id = ChatId(new_id);
+            }
+            Err(e) if attempt < MAX_SEND_ATTEMPTS => {
+                let backoff = Duration::from_secs(1u64 << attempt.min(5));
+                warn!(?chat_id, error = %e, attempt, ?backoff, "Ошибка отправки, повторяю с задержкой");
+                tokio::time::sleep(backoff).await;
+            }
+            Err(e) => {
+                return Err(anyhow!(e))
+                    .with_context(|| format!("Не удалось отправить сообщение в чат {:?} после {} попыток", chat_id, attempt));
+            }
+        }
+        if attempt >= MAX_SEND_ATTEMPTS {
+            return Err(anyhow!("Превышено число попыток отправки сообщения в чат {:?}", chat_id));
+        }
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// `html` задан, когда для события настроен HTML-шаблон, и передаётся всем
+    /// каналам; каналы, понимающие только простой текст (Slack-вебхуки),
+    /// игнорируют его и рендерят `plain`.
+    async fn send(&self, subject: &str, plain: &str, html: Option<&str>) -> Result<()>;
+}
+
+/// Доставляет в фиксированный список Telegram-чатов, предпочитая `html`, если он задан.
+pub struct TelegramNotifier {
+    bot: Bot,
+    chat_ids: Vec<ChatId>,
+    migrations: ChatMigrations,
+}
+
+impl TelegramNotifier {
+    pub fn new(bot: Bot, chat_ids: Vec<ChatId>, migrations: ChatMigrations) -> Self {
+        Self { bot, chat_ids, migrations }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    async fn send(&self, _subject: &str, plain: &str, html: Option<&str>) -> Result<()> {
+        let text = html.unwrap_or(plain);
+        let parse_mode = html.is_some().then_some(ParseMode::Html);
+        for &chat_id in &self.chat_ids {
+            if let Err(e) = send_resilient(&self.bot, &self.migrations, chat_id, text, parse_mode, None).await {
+                warn!(?chat_id, error = %e, "Не удалось отправить Telegram-уведомление через доп. канал");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookPayload {
+    text: String,
+}
+
+/// Доставляет в обобщённый Slack-style incoming webhook (обычный POST `{"text": "..."}`).
+pub struct WebhookNotifier {
+    client: Client,
+    hook_url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(hook_url: String) -> Self {
+        Self { client: Client::new(), hook_url }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn send(&self, subject: &str, plain: &str, _html: Option<&str>) -> Result<()> {
+        let payload = WebhookPayload { text: format!("*{}*\n{}", subject, plain) };
+        let resp = self.client.post(&self.hook_url).json(&payload).send().await?;
+        if !resp.status().is_success() {
+            bail!("Ошибка HTTP при отправке в вебхук: {}", resp.status());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrations_with(pairs: &[(i64, i64)]) -> ChatMigrations {
+        let mut map = HashMap::new();
+        for &(from, to) in pairs {
+            map.insert(from, to);
+        }
+        Arc::new(RwLock::new(map))
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chat_id_returns_original_when_no_remap() {
+        let migrations = migrations_with(&[]);
+        assert_eq!(resolve_chat_id(&migrations, ChatId(100)).await, ChatId(100));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chat_id_follows_single_remap() {
+        let migrations = migrations_with(&[(100, 200)]);
+        assert_eq!(resolve_chat_id(&migrations, ChatId(100)).await, ChatId(200));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chat_id_follows_chained_remaps() {
+        // 100 мигрировал в 200, который позже мигрировал в 300 — должны разрешиться сразу в 300.
+        let migrations = migrations_with(&[(100, 200), (200, 300)]);
+        assert_eq!(resolve_chat_id(&migrations, ChatId(100)).await, ChatId(300));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chat_id_does_not_loop_forever_on_self_remap() {
+        let migrations = migrations_with(&[(100, 100)]);
+        assert_eq!(resolve_chat_id(&migrations, ChatId(100)).await, ChatId(100));
+    }
+}